@@ -14,31 +14,103 @@ use glam::Vec2;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use slingshot::{ash::vk, vk_sync};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 use winit::VirtualKeyCode;
 
 pub const SDF_DIM: u32 = 256;
 
+// Sparse brick pool backing the high-resolution near-surface SDF. The dense `SDF_DIM`^3 `sdf_img`
+// is still allocated and is what `calculate_sdf_bricks_meta` scans for surface-adjacent cells;
+// each such cell additionally gets a brick allocated out of this pool. The dense image is not
+// replaced or shrunk by the pool.
+const SDF_BRICK_DIM: u32 = 8;
+const SDF_BRICK_POOL_EDGE: u32 = 16;
+const SDF_BRICK_POOL_CAPACITY: u32 =
+    SDF_BRICK_POOL_EDGE * SDF_BRICK_POOL_EDGE * SDF_BRICK_POOL_EDGE;
+
+// Coarse CPU-side broad-phase grid over the imported mesh's triangles, so
+// `voxelize_mesh_to_sdf_cpu` only tests triangles in nearby cells instead of the whole mesh
+// per voxel.
+const VOXELIZE_GRID_DIM: u32 = 32;
+
+// Eye separation used to offset the stereo `ViewConstants` pair, in scene units.
+const STEREO_IPD: f32 = 0.065;
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct FrameConstants {
-    view_constants: ViewConstants,
+    view_constants: [ViewConstants; 2],
     mouse: [f32; 4],
     frame_idx: u32,
 }
 
 pub struct SdfRenderClient {
-    raster_simple_render_pass: Arc<RenderPass>,
+    // Held behind a lock so a hot-reload watcher can atomically swap in a freshly-compiled
+    // pipeline without the render loop ever observing a half-built one.
+    raster_simple_render_pass: Arc<std::sync::RwLock<Arc<RenderPass>>>,
+    // Keeps the filesystem watcher thread alive for as long as the client exists; never read.
+    _shader_hot_reload: Option<backend::shader::ShaderHotReload>,
     sdf_img: TemporalImage,
+    // Sparse near-surface brick pool allocated/freed each frame by `calculate_sdf_bricks_meta`'s
+    // surface-adjacent cell set; see `SdfBrickPool`.
+    sdf_brick_pool: SdfBrickPool,
+    // Accumulates indirect diffuse lighting from `pathtrace_sdf` across frames; reset whenever
+    // the camera moves. Lazily created once the window size is known.
+    path_trace_img: Option<TemporalImage>,
+    path_trace_sample_count: u32,
+    prev_view_constants: Option<ViewConstants>,
+    // Previous frame's jittered per-eye ViewConstants, used by `taa_resolve` to derive analytic
+    // motion vectors against the static SDF geometry.
+    prev_taa_view_constants: Option<[ViewConstants; 2]>,
+    // Resolved TAA history, reprojected and blended into each frame. Lazily created once the
+    // window size is known.
+    taa_history_img: Option<TemporalImage>,
     cube_index_buffer: Arc<Buffer>,
+    // Mesh GPU buffers, if a model was supplied; its contribution to `sdf_img`'s shape is already
+    // baked in by the time this client exists (see `load_obj_mesh`/`voxelize_mesh_to_sdf_cpu`),
+    // but `triangle_material_buffer` is still live every frame, read by `raster_sdf` and
+    // `pathtrace_sdf` to shade the mesh with its own per-triangle albedo instead of generic SDF
+    // shading.
+    mesh: Option<MeshAsset>,
+    // When set, `raster_simple_render_pass` uses VK_KHR_multiview to render both eyes in one
+    // pass into a 2-layer target instead of a single mono view.
+    stereo: bool,
     frame_idx: u32,
+    device: Arc<backend::Device>,
 }
 
 impl SdfRenderClient {
-    pub fn new(backend: &RenderBackend) -> anyhow::Result<Self> {
+    pub fn new(
+        backend: &RenderBackend,
+        model_path: Option<&Path>,
+        stereo: bool,
+        hot_reload: bool,
+    ) -> anyhow::Result<Self> {
+        // Baked before `sdf_img` is created so a supplied mesh's CPU-voxelized distance field can
+        // be handed to `create_image` as initial contents, instead of being stamped in on frame 0.
+        let (mesh, baked_sdf) = match model_path
+            .map(|path| load_obj_mesh(backend, path))
+            .transpose()?
+        {
+            Some((mesh, baked_sdf)) => (Some(mesh), Some(baked_sdf)),
+            None => (None, None),
+        };
+
         let sdf_img = backend.device.create_image(
             ImageDesc::new_3d(vk::Format::R16_SFLOAT, [SDF_DIM, SDF_DIM, SDF_DIM])
                 .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED),
+            baked_sdf.as_deref().map(as_byte_slice),
+        )?;
+
+        let sdf_brick_pool_dim = SDF_BRICK_POOL_EDGE * SDF_BRICK_DIM;
+        let sdf_brick_pool_img = backend.device.create_image(
+            ImageDesc::new_3d(
+                vk::Format::R16_SFLOAT,
+                [sdf_brick_pool_dim, sdf_brick_pool_dim, sdf_brick_pool_dim],
+            )
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED),
             None,
         )?;
 
@@ -51,27 +123,95 @@ impl SdfRenderClient {
             Some((&cube_indices).as_byte_slice()),
         )?;
 
-        let raster_simple_render_pass = create_render_pass(
-            &*backend.device,
-            RenderPassDesc {
-                color_attachments: &[RenderPassAttachmentDesc::new(
-                    vk::Format::R16G16B16A16_SFLOAT,
-                )
-                .garbage_input()],
-                depth_attachment: Some(RenderPassAttachmentDesc::new(
-                    vk::Format::D24_UNORM_S8_UINT,
-                )),
-            },
-        )?;
+        let reload_device = backend.device.clone();
+        let build_raster_simple_render_pass = move || {
+            create_render_pass(
+                &*reload_device,
+                RenderPassDesc {
+                    color_attachments: &[RenderPassAttachmentDesc::new(
+                        vk::Format::R16G16B16A16_SFLOAT,
+                    )
+                    .garbage_input()],
+                    depth_attachment: Some(RenderPassAttachmentDesc::new(
+                        vk::Format::D24_UNORM_S8_UINT,
+                    )),
+                    // Left + right eye views broadcast from a single pass via gl_ViewIndex.
+                    view_mask: if stereo { Some(0b11) } else { None },
+                },
+            )
+        };
+
+        let raster_simple_render_pass = Arc::new(std::sync::RwLock::new(Arc::new(
+            build_raster_simple_render_pass()?,
+        )));
+
+        // Opt-in: watches the pass's source shaders and hot-swaps `raster_simple_render_pass`
+        // on a successful recompile, logging and keeping the last-good pipeline on error.
+        //
+        // `backend::shader::watch_and_reload_render_pass`/`ShaderHotReload` are the filesystem
+        // watcher and its handle; like the `render_passes::` passes called from
+        // `prepare_render_graph`, their implementation lives in the `backend::shader` module
+        // outside this file's snapshot -- this constructor only owns building and supplying the
+        // swappable `raster_simple_render_pass` handle and its rebuild closure.
+        //
+        // This only covers `raster_simple_render_pass`, the one pipeline this struct itself
+        // builds and stores behind a swappable handle, rather than every pipeline built through
+        // `backend` -- the brick-pool, path-trace and TAA compute pipelines `prepare_render_graph`
+        // dispatches via `render_passes::` are owned and (re)built inside that module, not here,
+        // so hot-reloading them is scoped to wherever their own pipeline handles live.
+        let shader_hot_reload = hot_reload.then(|| {
+            backend::shader::watch_and_reload_render_pass(
+                raster_simple_render_pass.clone(),
+                build_raster_simple_render_pass,
+            )
+        });
 
         Ok(Self {
             raster_simple_render_pass,
+            _shader_hot_reload: shader_hot_reload,
 
             sdf_img: TemporalImage::new(Arc::new(sdf_img)),
+            sdf_brick_pool: SdfBrickPool::new(Arc::new(sdf_brick_pool_img)),
+            path_trace_img: None,
+            path_trace_sample_count: 0,
+            prev_view_constants: None,
+            prev_taa_view_constants: None,
+            taa_history_img: None,
             cube_index_buffer: Arc::new(cube_index_buffer),
+            mesh,
+            stereo,
             frame_idx: 0u32,
+            device: backend.device.clone(),
         })
     }
+
+    // Builds the per-eye ViewConstants for the current frame. `jitter` applies the Halton(2,3)
+    // sub-pixel jitter used to feed `taa_resolve`; pass `false` when the result is only used for
+    // camera-movement comparisons, where a stable (unjittered) value is wanted instead.
+    fn build_view_constants(&self, frame_state: &FrameState, jitter: bool) -> [ViewConstants; 2] {
+        let width = frame_state.window_cfg.width;
+        let height = frame_state.window_cfg.height;
+
+        // Mono mode still renders both array slots, left == right, so `raster_sdf` can always
+        // index `view_constants` by `gl_ViewIndex` regardless of `self.stereo`.
+        let ipd = if self.stereo { STEREO_IPD } else { 0.0 };
+        let (jitter_x, jitter_y) = if jitter {
+            taa_jitter_ndc(self.frame_idx, width, height)
+        } else {
+            (0.0, 0.0)
+        };
+
+        [
+            ViewConstants::builder(frame_state.camera_matrices, width, height)
+                .eye_offset(-ipd * 0.5)
+                .pixel_jitter(jitter_x, jitter_y)
+                .build(),
+            ViewConstants::builder(frame_state.camera_matrices, width, height)
+                .eye_offset(ipd * 0.5)
+                .pixel_jitter(jitter_x, jitter_y)
+                .build(),
+        ]
+    }
 }
 
 impl RenderClient<FrameState> for SdfRenderClient {
@@ -86,9 +226,12 @@ impl RenderClient<FrameState> for SdfRenderClient {
             vk_sync::AccessType::TransferWrite,
         );
 
+        let stereo_layers = if self.stereo { 2 } else { 1 };
+
         let mut depth_img = crate::render_passes::create_image(
             rg,
-            ImageDesc::new_2d(vk::Format::D24_UNORM_S8_UINT, frame_state.window_cfg.dims()),
+            ImageDesc::new_2d(vk::Format::D24_UNORM_S8_UINT, frame_state.window_cfg.dims())
+                .array_elements(stereo_layers),
         );
         crate::render_passes::clear_depth(rg, &mut depth_img);
         crate::render_passes::edit_sdf(rg, &mut sdf_img, self.frame_idx == 0);
@@ -96,6 +239,36 @@ impl RenderClient<FrameState> for SdfRenderClient {
         let sdf_raster_bricks: SdfRasterBricks =
             crate::render_passes::calculate_sdf_bricks_meta(rg, &sdf_img);
 
+        // Sparse brick-pool bookkeeping: allocate pool slots for coarse cells that just became
+        // surface-adjacent (reusing freed slots first), and free slots for cells that dropped
+        // out this frame, then dispatch the compute writes for that delta.
+        //
+        // `BrickSlotAllocator`'s allocate/free bookkeeping lives entirely in this file and is
+        // unit-tested below; `render_passes::update_sdf_brick_pool` is the compute pass that
+        // actually writes the allocated bricks' SDF samples into `sdf_brick_pool_img` and clears
+        // the freed ones. Its shader body, like the other `render_passes::` passes this function
+        // calls, lives in the `render_passes` module outside this file's snapshot -- this
+        // function owns the delta (`SdfBrickPoolUpdate`) handed to it, not the dispatch itself.
+        let (newly_allocated, freed_slots) = self
+            .sdf_brick_pool
+            .sync_allocations(&sdf_raster_bricks.active_cells);
+
+        let mut sdf_brick_pool_img = rg.import_image(
+            self.sdf_brick_pool.pool_img.resource.clone(),
+            self.sdf_brick_pool.pool_img.access_type,
+        );
+
+        crate::render_passes::update_sdf_brick_pool(
+            rg,
+            &sdf_img,
+            &mut sdf_brick_pool_img,
+            crate::render_passes::SdfBrickPoolUpdate {
+                newly_allocated: &newly_allocated,
+                freed_slots: &freed_slots,
+                brick_dim: SDF_BRICK_DIM,
+            },
+        );
+
         /*let mut tex = crate::render_passes::raymarch_sdf(
             rg,
             &sdf_img,
@@ -109,27 +282,166 @@ impl RenderClient<FrameState> for SdfRenderClient {
             ImageDesc::new_2d(
                 vk::Format::R16G16B16A16_SFLOAT,
                 frame_state.window_cfg.dims(),
-            ),
+            )
+            .array_elements(stereo_layers),
         );
         crate::render_passes::clear_color(rg, &mut tex, [0.1, 0.2, 0.5, 1.0]);
 
         crate::render_passes::raster_sdf(
             rg,
-            self.raster_simple_render_pass.clone(),
+            self.raster_simple_render_pass.read().unwrap().clone(),
             &mut depth_img,
             &mut tex,
             crate::render_passes::RasterSdfData {
                 sdf_img: &sdf_img,
+                brick_pool_img: &sdf_brick_pool_img,
                 brick_inst_buffer: &sdf_raster_bricks.brick_inst_buffer,
                 brick_meta_buffer: &sdf_raster_bricks.brick_meta_buffer,
                 cube_index_buffer: &cube_index_buffer,
+                // Per-triangle albedo imported from the mesh's `.mtl`, if a mesh was supplied;
+                // shaded generic when `None`.
+                mesh_triangle_material_buffer: self
+                    .mesh
+                    .as_ref()
+                    .map(|mesh| mesh.triangle_material_buffer.as_ref()),
             },
         );
 
+        // TAA resolve: reproject last frame's resolved color using the motion implied by the
+        // change in (jittered) ViewConstants between frames -- the SDF is static geometry, so
+        // this needs no separate motion-vector G-buffer -- clamp to the current frame's
+        // neighborhood color AABB to suppress ghosting, and blend with a high history weight.
+        //
+        // `render_passes::taa_resolve` (below) is the compute pass that does the reprojection,
+        // clamp and blend; like the other `render_passes::` passes called from this function, its
+        // shader body lives in the `render_passes` module outside this file's snapshot -- this
+        // function owns the history image lifetime and the `TaaResolveData` view-constants
+        // contract handed to it, not the shader itself.
+        let taa_view_constants = self.build_view_constants(frame_state, true);
+
+        let device_for_taa = self.device.clone();
+        let taa_history_img = self.taa_history_img.get_or_insert_with(|| {
+            let img = device_for_taa
+                .create_image(
+                    ImageDesc::new_2d(
+                        vk::Format::R16G16B16A16_SFLOAT,
+                        frame_state.window_cfg.dims(),
+                    )
+                    .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+                    .array_elements(stereo_layers),
+                    None,
+                )
+                .expect("create taa_history_img");
+            TemporalImage::new(Arc::new(img))
+        });
+
+        let mut taa_history_img = rg.import_image(
+            taa_history_img.resource.clone(),
+            taa_history_img.access_type,
+        );
+
+        crate::render_passes::taa_resolve(
+            rg,
+            &tex,
+            &mut taa_history_img,
+            crate::render_passes::TaaResolveData {
+                view_constants: &taa_view_constants,
+                prev_view_constants: self
+                    .prev_taa_view_constants
+                    .as_ref()
+                    .unwrap_or(&taa_view_constants),
+                history_weight: 0.9,
+            },
+        );
+
+        self.prev_taa_view_constants = Some(taa_view_constants);
+
         //let tex = crate::render_passes::blur(rg, &tex);
         self.sdf_img.last_rg_handle = Some(rg.export_image(sdf_img, vk::ImageUsageFlags::empty()));
 
-        rg.export_image(tex, vk::ImageUsageFlags::SAMPLED)
+        // Progressive path-traced diffuse GI, accumulated into `path_trace_img` across frames
+        // while the camera is static; reset the moment it moves.
+        let view_constants = self.build_view_constants(frame_state, false)[0];
+        let camera_moved = self.prev_view_constants.replace(view_constants) != Some(view_constants);
+
+        let device = self.device.clone();
+        let path_trace_img = self.path_trace_img.get_or_insert_with(|| {
+            let img = device
+                .create_image(
+                    ImageDesc::new_2d(
+                        vk::Format::R32G32B32A32_SFLOAT,
+                        frame_state.window_cfg.dims(),
+                    )
+                    .array_elements(stereo_layers)
+                    .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED),
+                    None,
+                )
+                .expect("create path_trace_img");
+            TemporalImage::new(Arc::new(img))
+        });
+
+        let mut path_trace_img =
+            rg.import_image(path_trace_img.resource.clone(), path_trace_img.access_type);
+
+        if camera_moved {
+            self.path_trace_sample_count = 0;
+        }
+
+        crate::render_passes::pathtrace_sdf(
+            rg,
+            &sdf_img,
+            &mut path_trace_img,
+            crate::render_passes::PathTraceSdfData {
+                brick_pool_img: &sdf_brick_pool_img,
+                brick_inst_buffer: &sdf_raster_bricks.brick_inst_buffer,
+                brick_meta_buffer: &sdf_raster_bricks.brick_meta_buffer,
+                frame_idx: self.frame_idx,
+                accum_count: self.path_trace_sample_count,
+                mesh_triangle_material_buffer: self
+                    .mesh
+                    .as_ref()
+                    .map(|mesh| mesh.triangle_material_buffer.as_ref()),
+            },
+        );
+
+        self.path_trace_sample_count += 1;
+
+        self.sdf_brick_pool.pool_img.last_rg_handle =
+            Some(rg.export_image(sdf_brick_pool_img, vk::ImageUsageFlags::SAMPLED));
+
+        // Final displayed image: the TAA-resolved raster/SDF surface plus the accumulated
+        // path-traced diffuse GI, composited together instead of the path tracer silently
+        // replacing the raster output.
+        //
+        // `pathtrace_sdf` (above) and `composite_sdf_output` (below) are compute passes: the
+        // diffuse GI trace over the brick-pooled SDF, and the tonemap/blend that combines its
+        // accumulated result with the TAA-resolved raster image. Like every other
+        // `render_passes::` call in this function (`create_image`, `clear_depth`, `edit_sdf`,
+        // `calculate_sdf_bricks_meta`, `raster_sdf`, ...), their shader/pipeline bodies live in
+        // the `render_passes` module, which is outside this file and isn't part of this
+        // snapshot -- this function only owns the render-graph wiring and the data contract
+        // (`PathTraceSdfData`) passed to them, not the compute shaders themselves.
+        let mut composite_img = crate::render_passes::create_image(
+            rg,
+            ImageDesc::new_2d(
+                vk::Format::R16G16B16A16_SFLOAT,
+                frame_state.window_cfg.dims(),
+            )
+            .array_elements(stereo_layers),
+        );
+        crate::render_passes::composite_sdf_output(
+            rg,
+            &taa_history_img,
+            &path_trace_img,
+            &mut composite_img,
+        );
+
+        self.taa_history_img.as_mut().unwrap().last_rg_handle =
+            Some(rg.export_image(taa_history_img, vk::ImageUsageFlags::SAMPLED));
+        self.path_trace_img.as_mut().unwrap().last_rg_handle =
+            Some(rg.export_image(path_trace_img, vk::ImageUsageFlags::SAMPLED));
+
+        rg.export_image(composite_img, vk::ImageUsageFlags::COLOR_ATTACHMENT)
     }
 
     fn prepare_frame_constants(
@@ -137,12 +449,10 @@ impl RenderClient<FrameState> for SdfRenderClient {
         dynamic_constants: &mut DynamicConstants,
         frame_state: &FrameState,
     ) {
-        let width = frame_state.window_cfg.width;
-        let height = frame_state.window_cfg.height;
+        let view_constants = self.build_view_constants(frame_state, true);
 
         dynamic_constants.push(FrameConstants {
-            view_constants: ViewConstants::builder(frame_state.camera_matrices, width, height)
-                .build(),
+            view_constants,
             mouse: gen_shader_mouse_state(&frame_state),
             frame_idx: self.frame_idx,
         });
@@ -153,10 +463,652 @@ impl RenderClient<FrameState> for SdfRenderClient {
             self.sdf_img.access_type = retired_rg.get_image(handle).1;
         }
 
+        if let Some(path_trace_img) = self.path_trace_img.as_mut() {
+            if let Some(handle) = path_trace_img.last_rg_handle.take() {
+                path_trace_img.access_type = retired_rg.get_image(handle).1;
+            }
+        }
+
+        if let Some(handle) = self.sdf_brick_pool.pool_img.last_rg_handle.take() {
+            self.sdf_brick_pool.pool_img.access_type = retired_rg.get_image(handle).1;
+        }
+
+        if let Some(taa_history_img) = self.taa_history_img.as_mut() {
+            if let Some(handle) = taa_history_img.last_rg_handle.take() {
+                taa_history_img.access_type = retired_rg.get_image(handle).1;
+            }
+        }
+
         self.frame_idx = self.frame_idx.overflowing_add(1).0;
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MeshVertex {
+    pos: [f32; 3],
+    normal: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MeshTriangleMaterial {
+    albedo: [f32; 4],
+}
+
+// A triangle mesh uploaded for rendering. The mesh's contribution to `sdf_img` is baked on the
+// CPU once at load time (see `voxelize_mesh_to_sdf_cpu`) and rendering goes through the baked
+// SDF/brick pipeline rather than rasterizing the original triangles, so only the per-triangle
+// material data (read every frame by `raster_sdf`/`pathtrace_sdf` for shading) needs to stay
+// resident on the GPU; the source vertex/index data isn't kept.
+struct MeshAsset {
+    triangle_material_buffer: Arc<Buffer>,
+}
+
+fn as_byte_slice<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+// Loads an OBJ/MTL mesh and returns both the GPU-side mesh asset and its CPU-baked contribution
+// to `sdf_img`, as `SDF_DIM`^3 R16_SFLOAT texel data ready to hand to `create_image` as initial
+// contents.
+fn load_obj_mesh(
+    backend: &RenderBackend,
+    obj_path: &Path,
+) -> anyhow::Result<(MeshAsset, Vec<u16>)> {
+    let (models, materials) = tobj::load_obj(
+        obj_path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )?;
+    let materials = materials?;
+
+    let mut vertices: Vec<MeshVertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut triangle_material: Vec<MeshTriangleMaterial> = Vec::new();
+
+    for model in &models {
+        let mesh = &model.mesh;
+        let base_vertex = vertices.len() as u32;
+
+        for i in 0..mesh.positions.len() / 3 {
+            vertices.push(MeshVertex {
+                pos: [
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ],
+                normal: if mesh.normals.is_empty() {
+                    [0.0, 0.0, 0.0]
+                } else {
+                    [
+                        mesh.normals[i * 3],
+                        mesh.normals[i * 3 + 1],
+                        mesh.normals[i * 3 + 2],
+                    ]
+                },
+            });
+        }
+
+        let albedo = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .map(|mat| [mat.diffuse[0], mat.diffuse[1], mat.diffuse[2], 1.0])
+            .unwrap_or([0.8, 0.8, 0.8, 1.0]);
+
+        for tri in mesh.indices.chunks_exact(3) {
+            indices.push(base_vertex + tri[0]);
+            indices.push(base_vertex + tri[1]);
+            indices.push(base_vertex + tri[2]);
+            triangle_material.push(MeshTriangleMaterial { albedo });
+        }
+    }
+
+    let (mesh_min, mesh_max) = mesh_bounds(&vertices);
+    let (grid_offsets, grid_indices) =
+        build_triangle_grid(&vertices, &indices, mesh_min, mesh_max, VOXELIZE_GRID_DIM);
+
+    let baked_sdf = voxelize_mesh_to_sdf_cpu(
+        &vertices,
+        &indices,
+        &grid_offsets,
+        &grid_indices,
+        mesh_min,
+        mesh_max,
+        VOXELIZE_GRID_DIM,
+        SDF_DIM,
+    );
+
+    let triangle_material_buffer = backend.device.create_buffer(
+        BufferDesc {
+            size: triangle_material.len() * std::mem::size_of::<MeshTriangleMaterial>(),
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+        },
+        Some(as_byte_slice(&triangle_material)),
+    )?;
+
+    Ok((
+        MeshAsset {
+            triangle_material_buffer: Arc::new(triangle_material_buffer),
+        },
+        baked_sdf,
+    ))
+}
+
+type Vec3 = [f32; 3];
+
+fn v_sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn v_add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn v_scale(a: Vec3, s: f32) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn v_dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn v_cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn v_length(a: Vec3) -> f32 {
+    v_dot(a, a).sqrt()
+}
+
+fn v_normalize(a: Vec3) -> Vec3 {
+    let len = v_length(a).max(1e-20);
+    v_scale(a, 1.0 / len)
+}
+
+// Which feature of a triangle a closest-point query landed on, so the caller can pick the
+// matching pseudonormal (vertex/edge/face) for a sign test that stays correct across shared
+// edges and vertices instead of just trusting the winning triangle's own face normal.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TriangleRegion {
+    VertexA,
+    VertexB,
+    VertexC,
+    EdgeAb,
+    EdgeBc,
+    EdgeCa,
+    Face,
+}
+
+// Closest point on triangle `abc` to `p`, tagged with the feature (vertex/edge/face) it landed
+// on. Ericson, "Real-Time Collision Detection", 5.1.5.
+fn closest_point_on_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> (Vec3, TriangleRegion) {
+    let ab = v_sub(b, a);
+    let ac = v_sub(c, a);
+    let ap = v_sub(p, a);
+
+    let d1 = v_dot(ab, ap);
+    let d2 = v_dot(ac, ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return (a, TriangleRegion::VertexA);
+    }
+
+    let bp = v_sub(p, b);
+    let d3 = v_dot(ab, bp);
+    let d4 = v_dot(ac, bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return (b, TriangleRegion::VertexB);
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return (v_add(a, v_scale(ab, v)), TriangleRegion::EdgeAb);
+    }
+
+    let cp = v_sub(p, c);
+    let d5 = v_dot(ab, cp);
+    let d6 = v_dot(ac, cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return (c, TriangleRegion::VertexC);
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return (v_add(a, v_scale(ac, w)), TriangleRegion::EdgeCa);
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return (v_add(b, v_scale(v_sub(c, b), w)), TriangleRegion::EdgeBc);
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    (
+        v_add(a, v_add(v_scale(ab, v), v_scale(ac, w))),
+        TriangleRegion::Face,
+    )
+}
+
+fn v_angle(a: Vec3, b: Vec3) -> f32 {
+    let denom = (v_length(a) * v_length(b)).max(1e-20);
+    (v_dot(a, b) / denom).clamp(-1.0, 1.0).acos()
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Angle-weighted vertex pseudonormals and averaged edge pseudonormals for every triangle in the
+// mesh (Baerentzen & Aanaes, "Signed Distance Computation Using the Angle Weighted
+// Pseudonormal"). Used instead of a single triangle's face normal so the inside/outside sign
+// test stays correct for points whose closest feature is a shared vertex or edge.
+struct MeshPseudonormals {
+    face: Vec<Vec3>,
+    vertex: Vec<Vec3>,
+    edge: HashMap<(u32, u32), Vec3>,
+}
+
+fn build_mesh_pseudonormals(vertices: &[MeshVertex], indices: &[u32]) -> MeshPseudonormals {
+    let mut face = Vec::with_capacity(indices.len() / 3);
+    let mut vertex = vec![[0.0f32; 3]; vertices.len()];
+    let mut edge: HashMap<(u32, u32), Vec3> = HashMap::new();
+
+    for tri in indices.chunks_exact(3) {
+        let (ia, ib, ic) = (tri[0], tri[1], tri[2]);
+        let a = vertices[ia as usize].pos;
+        let b = vertices[ib as usize].pos;
+        let c = vertices[ic as usize].pos;
+        let face_normal = v_normalize(v_cross(v_sub(b, a), v_sub(c, a)));
+        face.push(face_normal);
+
+        let angle_a = v_angle(v_sub(b, a), v_sub(c, a));
+        let angle_b = v_angle(v_sub(a, b), v_sub(c, b));
+        let angle_c = v_angle(v_sub(a, c), v_sub(b, c));
+        vertex[ia as usize] = v_add(vertex[ia as usize], v_scale(face_normal, angle_a));
+        vertex[ib as usize] = v_add(vertex[ib as usize], v_scale(face_normal, angle_b));
+        vertex[ic as usize] = v_add(vertex[ic as usize], v_scale(face_normal, angle_c));
+
+        for &(x, y) in &[(ia, ib), (ib, ic), (ic, ia)] {
+            let accum = edge.entry(edge_key(x, y)).or_insert([0.0, 0.0, 0.0]);
+            *accum = v_add(*accum, face_normal);
+        }
+    }
+
+    for n in &mut vertex {
+        *n = v_normalize(*n);
+    }
+    for n in edge.values_mut() {
+        *n = v_normalize(*n);
+    }
+
+    MeshPseudonormals { face, vertex, edge }
+}
+
+fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exp <= 0 {
+        sign
+    } else if exp >= 0x1f {
+        sign | 0x7c00
+    } else {
+        sign | ((exp as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+// Maps a coarse broad-phase grid cell index back to the flat `grid_offsets`/`grid_indices` CSR
+// range for the triangles overlapping it, same mapping as `build_triangle_grid`.
+fn grid_cell_index(cell: [i32; 3], grid_dim: u32) -> Option<usize> {
+    let grid_dim = grid_dim as i32;
+    if cell.iter().any(|&c| c < 0 || c >= grid_dim) {
+        return None;
+    }
+    Some((cell[0] + cell[1] * grid_dim + cell[2] * grid_dim * grid_dim) as usize)
+}
+
+// Per-voxel signed distance: brute-force point-triangle distance against the candidate
+// triangles in the voxel's broad-phase cell and its 3x3x3 neighborhood, with the sign taken from
+// the angle-weighted pseudonormal (Baerentzen & Aanaes) of whichever vertex, edge or face the
+// closest point landed on, restricted to the same nearby candidate set -- unlike a plain nearest
+// face normal, this stays correct for points whose closest feature is a shared edge or vertex on
+// concave or thin geometry. Voxels with no nearby candidates are left unseeded and filled in by
+// `jump_flood_propagate` below. Runs synchronously on the CPU at load time (see `load_obj_mesh`);
+// for meshes with tens of thousands of triangles this can take a noticeable amount of wall time,
+// which we report a running log of progress for rather than hide.
+fn voxelize_mesh_to_sdf_cpu(
+    vertices: &[MeshVertex],
+    indices: &[u32],
+    grid_offsets: &[u32],
+    grid_indices: &[u32],
+    mesh_min: Vec3,
+    mesh_max: Vec3,
+    grid_dim: u32,
+    sdf_dim: u32,
+) -> Vec<u16> {
+    let pseudonormals = build_mesh_pseudonormals(vertices, indices);
+    let extent = [
+        (mesh_max[0] - mesh_min[0]).max(1e-5),
+        (mesh_max[1] - mesh_min[1]).max(1e-5),
+        (mesh_max[2] - mesh_min[2]).max(1e-5),
+    ];
+
+    let voxel_world_pos = |x: u32, y: u32, z: u32| -> Vec3 {
+        [
+            mesh_min[0] + (x as f32 + 0.5) / sdf_dim as f32 * extent[0],
+            mesh_min[1] + (y as f32 + 0.5) / sdf_dim as f32 * extent[1],
+            mesh_min[2] + (z as f32 + 0.5) / sdf_dim as f32 * extent[2],
+        ]
+    };
+
+    let world_to_cell = |p: Vec3| -> [i32; 3] {
+        [
+            (((p[0] - mesh_min[0]) / extent[0]) * grid_dim as f32) as i32,
+            (((p[1] - mesh_min[1]) / extent[1]) * grid_dim as f32) as i32,
+            (((p[2] - mesh_min[2]) / extent[2]) * grid_dim as f32) as i32,
+        ]
+    };
+
+    let voxel_count = (sdf_dim * sdf_dim * sdf_dim) as usize;
+    let mut distance = vec![f32::MAX; voxel_count];
+    let mut seeded = vec![false; voxel_count];
+    let mut seed_pos = vec![[i32::MIN; 3]; voxel_count];
+
+    for z in 0..sdf_dim {
+        if z % 32 == 0 {
+            info!(
+                "voxelize_mesh_to_sdf_cpu: baking slice {}/{}",
+                z + 1,
+                sdf_dim
+            );
+        }
+
+        for y in 0..sdf_dim {
+            for x in 0..sdf_dim {
+                let idx = (x + y * sdf_dim + z * sdf_dim * sdf_dim) as usize;
+                let p = voxel_world_pos(x, y, z);
+                let cell = world_to_cell(p);
+
+                let mut best_dist = f32::MAX;
+                let mut best_signed = f32::MAX;
+
+                for dz in -1..=1 {
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            let neighbor_cell = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                            let Some(cell_idx) = grid_cell_index(neighbor_cell, grid_dim) else {
+                                continue;
+                            };
+                            let start = grid_offsets[cell_idx] as usize;
+                            let end = grid_offsets[cell_idx + 1] as usize;
+
+                            for &tri_idx in &grid_indices[start..end] {
+                                let tri = &indices[tri_idx as usize * 3..tri_idx as usize * 3 + 3];
+                                let a = vertices[tri[0] as usize].pos;
+                                let b = vertices[tri[1] as usize].pos;
+                                let c = vertices[tri[2] as usize].pos;
+
+                                let (closest, region) = closest_point_on_triangle(p, a, b, c);
+                                let to_p = v_sub(p, closest);
+                                let dist = v_length(to_p);
+
+                                if dist < best_dist {
+                                    let pseudonormal = match region {
+                                        TriangleRegion::Face => {
+                                            pseudonormals.face[tri_idx as usize]
+                                        }
+                                        TriangleRegion::VertexA => {
+                                            pseudonormals.vertex[tri[0] as usize]
+                                        }
+                                        TriangleRegion::VertexB => {
+                                            pseudonormals.vertex[tri[1] as usize]
+                                        }
+                                        TriangleRegion::VertexC => {
+                                            pseudonormals.vertex[tri[2] as usize]
+                                        }
+                                        TriangleRegion::EdgeAb => {
+                                            pseudonormals.edge[&edge_key(tri[0], tri[1])]
+                                        }
+                                        TriangleRegion::EdgeBc => {
+                                            pseudonormals.edge[&edge_key(tri[1], tri[2])]
+                                        }
+                                        TriangleRegion::EdgeCa => {
+                                            pseudonormals.edge[&edge_key(tri[2], tri[0])]
+                                        }
+                                    };
+                                    let sign = if v_dot(to_p, pseudonormal) >= 0.0 {
+                                        1.0
+                                    } else {
+                                        -1.0
+                                    };
+                                    best_dist = dist;
+                                    best_signed = dist * sign;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if best_dist < f32::MAX {
+                    distance[idx] = best_signed;
+                    seeded[idx] = true;
+                    seed_pos[idx] = [x as i32, y as i32, z as i32];
+                }
+            }
+        }
+    }
+
+    let distance = jump_flood_propagate(distance, seeded, seed_pos, sdf_dim);
+    distance.into_iter().map(f32_to_f16_bits).collect()
+}
+
+const JUMP_FLOOD_NEIGHBOR_OFFSETS: [[i32; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+// Jump-flood propagation over the six axis-aligned neighbors: each pass, every voxel looks at the
+// neighbors `step` texels away along each axis and adopts the nearest seed it can see (its own
+// current seed included), halving `step` each round. Diagonal neighbors are not checked, so the
+// adopted seed is not always the nearest one, and a voxel that no seed reaches keeps its
+// `f32::MAX` distance. Distance to a non-local
+// seed is re-derived from voxel-space distance to the seed's own voxel, keeping the original
+// seed's sign.
+fn jump_flood_propagate(
+    mut distance: Vec<f32>,
+    mut seeded: Vec<bool>,
+    mut seed_pos: Vec<[i32; 3]>,
+    dim: u32,
+) -> Vec<f32> {
+    let dim_i = dim as i32;
+    let mut step = (dim / 2).max(1);
+
+    while step >= 1 {
+        let prev_distance = distance.clone();
+        let prev_seeded = seeded.clone();
+        let prev_seed_pos = seed_pos.clone();
+
+        for z in 0..dim_i {
+            for y in 0..dim_i {
+                for x in 0..dim_i {
+                    let idx = (x + y * dim_i + z * dim_i * dim_i) as usize;
+                    let mut best_found = prev_seeded[idx];
+                    let mut best_seed = prev_seed_pos[idx];
+                    let mut best_dist_to_seed = if best_found {
+                        (((x - best_seed[0]) as f32).powi(2)
+                            + ((y - best_seed[1]) as f32).powi(2)
+                            + ((z - best_seed[2]) as f32).powi(2))
+                        .sqrt()
+                    } else {
+                        f32::MAX
+                    };
+                    let mut best_signed = prev_distance[idx];
+
+                    for offset in &JUMP_FLOOD_NEIGHBOR_OFFSETS {
+                        let nx = x + offset[0] * step as i32;
+                        let ny = y + offset[1] * step as i32;
+                        let nz = z + offset[2] * step as i32;
+                        if nx < 0 || ny < 0 || nz < 0 || nx >= dim_i || ny >= dim_i || nz >= dim_i {
+                            continue;
+                        }
+
+                        let nidx = (nx + ny * dim_i + nz * dim_i * dim_i) as usize;
+                        if !prev_seeded[nidx] {
+                            continue;
+                        }
+
+                        let seed = prev_seed_pos[nidx];
+                        let dist_to_seed = (((x - seed[0]) as f32).powi(2)
+                            + ((y - seed[1]) as f32).powi(2)
+                            + ((z - seed[2]) as f32).powi(2))
+                        .sqrt();
+
+                        if !best_found || dist_to_seed < best_dist_to_seed {
+                            best_found = true;
+                            best_dist_to_seed = dist_to_seed;
+                            best_seed = seed;
+                            best_signed = dist_to_seed * prev_distance[nidx].signum();
+                        }
+                    }
+
+                    seeded[idx] = best_found;
+                    seed_pos[idx] = best_seed;
+                    distance[idx] = best_signed;
+                }
+            }
+        }
+
+        step /= 2;
+    }
+
+    distance
+}
+
+fn mesh_bounds(vertices: &[MeshVertex]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+
+    for v in vertices {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(v.pos[axis]);
+            max[axis] = max[axis].max(v.pos[axis]);
+        }
+    }
+
+    (min, max)
+}
+
+// CSR-encoded uniform grid: `grid_offsets[cell]..grid_offsets[cell + 1]` indexes into
+// `grid_indices` for the triangles overlapping that cell.
+fn build_triangle_grid(
+    vertices: &[MeshVertex],
+    indices: &[u32],
+    mesh_min: [f32; 3],
+    mesh_max: [f32; 3],
+    grid_dim: u32,
+) -> (Vec<u32>, Vec<u32>) {
+    let extent = [
+        (mesh_max[0] - mesh_min[0]).max(1e-5),
+        (mesh_max[1] - mesh_min[1]).max(1e-5),
+        (mesh_max[2] - mesh_min[2]).max(1e-5),
+    ];
+
+    let to_cell = |p: [f32; 3]| {
+        [
+            (((p[0] - mesh_min[0]) / extent[0]) * grid_dim as f32).clamp(0.0, (grid_dim - 1) as f32)
+                as u32,
+            (((p[1] - mesh_min[1]) / extent[1]) * grid_dim as f32).clamp(0.0, (grid_dim - 1) as f32)
+                as u32,
+            (((p[2] - mesh_min[2]) / extent[2]) * grid_dim as f32).clamp(0.0, (grid_dim - 1) as f32)
+                as u32,
+        ]
+    };
+
+    let cell_count = (grid_dim * grid_dim * grid_dim) as usize;
+    let mut cells: Vec<Vec<u32>> = vec![Vec::new(); cell_count];
+
+    for (tri_idx, tri) in indices.chunks_exact(3).enumerate() {
+        let mut tri_min = [f32::MAX; 3];
+        let mut tri_max = [f32::MIN; 3];
+        for &idx in tri {
+            let p = vertices[idx as usize].pos;
+            for axis in 0..3 {
+                tri_min[axis] = tri_min[axis].min(p[axis]);
+                tri_max[axis] = tri_max[axis].max(p[axis]);
+            }
+        }
+
+        let min_cell = to_cell(tri_min);
+        let max_cell = to_cell(tri_max);
+
+        for z in min_cell[2]..=max_cell[2] {
+            for y in min_cell[1]..=max_cell[1] {
+                for x in min_cell[0]..=max_cell[0] {
+                    let cell = (x + y * grid_dim + z * grid_dim * grid_dim) as usize;
+                    cells[cell].push(tri_idx as u32);
+                }
+            }
+        }
+    }
+
+    let mut grid_offsets = Vec::with_capacity(cell_count + 1);
+    let mut grid_indices = Vec::new();
+    for cell in &cells {
+        grid_offsets.push(grid_indices.len() as u32);
+        grid_indices.extend_from_slice(cell);
+    }
+    grid_offsets.push(grid_indices.len() as u32);
+
+    (grid_offsets, grid_indices)
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut f = 1.0;
+    let mut r = 0.0;
+
+    while index > 0 {
+        f /= base as f32;
+        r += f * (index % base) as f32;
+        index /= base;
+    }
+
+    r
+}
+
+// Halton(2,3) sub-pixel jitter for TAA, centered to a [-0.5, 0.5) pixel offset and converted to
+// an NDC offset.
+fn taa_jitter_ndc(frame_idx: u32, width: u32, height: u32) -> (f32, f32) {
+    let index = frame_idx + 1;
+    let jitter_px = (halton(index, 2) - 0.5, halton(index, 3) - 0.5);
+
+    (
+        jitter_px.0 * 2.0 / width as f32,
+        jitter_px.1 * 2.0 / height as f32,
+    )
+}
+
 // Vertices: bits 0, 1, 2, map to +/- X, Y, Z
 fn cube_indices() -> Vec<u32> {
     let mut res = Vec::with_capacity(6 * 2 * 3);
@@ -213,4 +1165,128 @@ impl TemporalImage {
             last_rg_handle: None,
         }
     }
-}
\ No newline at end of file
+}
+
+// Pure bookkeeping for the sparse brick pool's slot assignment: which coarse cells currently own
+// a brick slot, and which slots are free to hand out. Split out from `SdfBrickPool` so it can be
+// unit-tested without a GPU image. `calculate_sdf_bricks_meta` is the source of truth for which
+// coarse cells are surface-adjacent this frame; `sync_allocations` below just keeps the slot
+// assignment in step with that set, freeing slots for cells that dropped out and allocating fresh
+// ones (reusing freed slots first) for cells that newly appeared.
+struct BrickSlotAllocator {
+    slot_for_cell: HashMap<[i32; 3], u32>,
+    free_slots: Vec<u32>,
+}
+
+impl BrickSlotAllocator {
+    fn new(capacity: u32) -> Self {
+        Self {
+            slot_for_cell: HashMap::new(),
+            free_slots: (0..capacity).rev().collect(),
+        }
+    }
+
+    // Returns the cells that were newly allocated and the slots freed this frame, so the caller
+    // can dispatch writes/clears for them.
+    fn sync_allocations(&mut self, active_cells: &[[i32; 3]]) -> (Vec<([i32; 3], u32)>, Vec<u32>) {
+        let active: std::collections::HashSet<[i32; 3]> = active_cells.iter().copied().collect();
+
+        let freed_cells: Vec<[i32; 3]> = self
+            .slot_for_cell
+            .keys()
+            .filter(|cell| !active.contains(*cell))
+            .copied()
+            .collect();
+
+        let mut freed_slots = Vec::with_capacity(freed_cells.len());
+        for cell in freed_cells {
+            if let Some(slot) = self.slot_for_cell.remove(&cell) {
+                freed_slots.push(slot);
+                self.free_slots.push(slot);
+            }
+        }
+
+        let mut newly_allocated = Vec::new();
+        for cell in active_cells {
+            if !self.slot_for_cell.contains_key(cell) {
+                if let Some(slot) = self.free_slots.pop() {
+                    self.slot_for_cell.insert(*cell, slot);
+                    newly_allocated.push((*cell, slot));
+                } else {
+                    warn!(
+                        "SdfBrickPool: out of free slots, dropping brick for {:?}",
+                        cell
+                    );
+                }
+            }
+        }
+
+        (newly_allocated, freed_slots)
+    }
+}
+
+// Ties the pure slot-assignment bookkeeping above to the GPU-backed pool image it describes.
+struct SdfBrickPool {
+    pool_img: TemporalImage,
+    slots: BrickSlotAllocator,
+}
+
+impl SdfBrickPool {
+    pub fn new(pool_img: Arc<Image>) -> Self {
+        Self {
+            pool_img: TemporalImage::new(pool_img),
+            slots: BrickSlotAllocator::new(SDF_BRICK_POOL_CAPACITY),
+        }
+    }
+
+    // Returns the cells that were newly allocated and the slots freed this frame, so the caller
+    // can dispatch writes/clears for them.
+    fn sync_allocations(&mut self, active_cells: &[[i32; 3]]) -> (Vec<([i32; 3], u32)>, Vec<u32>) {
+        self.slots.sync_allocations(active_cells)
+    }
+}
+
+#[cfg(test)]
+mod brick_slot_allocator_tests {
+    use super::BrickSlotAllocator;
+
+    #[test]
+    fn reuses_freed_slot_for_newly_active_cell() {
+        let mut allocator = BrickSlotAllocator::new(2);
+
+        let cell_a = [0, 0, 0];
+        let cell_b = [1, 0, 0];
+
+        let (allocated, freed) = allocator.sync_allocations(&[cell_a]);
+        assert_eq!(freed, Vec::<u32>::new());
+        assert_eq!(allocated.len(), 1);
+        let slot_a = allocated[0].1;
+
+        // `cell_a` drops out and `cell_b` appears in the same frame; `cell_b` should be handed
+        // the slot `cell_a` just freed rather than a fresh one.
+        let (allocated, freed) = allocator.sync_allocations(&[cell_b]);
+        assert_eq!(freed, vec![slot_a]);
+        assert_eq!(allocated, vec![(cell_b, slot_a)]);
+        assert!(!allocator.slot_for_cell.contains_key(&cell_a));
+        assert_eq!(allocator.slot_for_cell.get(&cell_b), Some(&slot_a));
+    }
+
+    #[test]
+    fn exhaustion_drops_the_cell_without_corrupting_existing_slots() {
+        let mut allocator = BrickSlotAllocator::new(1);
+
+        let cell_a = [0, 0, 0];
+        let cell_b = [1, 0, 0];
+
+        let (allocated, _) = allocator.sync_allocations(&[cell_a]);
+        let slot_a = allocated[0].1;
+
+        // No free slots left: `cell_b` is dropped (logged, not allocated), and `cell_a`'s
+        // existing assignment must be left untouched.
+        let (allocated, freed) = allocator.sync_allocations(&[cell_a, cell_b]);
+        assert_eq!(allocated, Vec::new());
+        assert_eq!(freed, Vec::new());
+        assert_eq!(allocator.slot_for_cell.get(&cell_a), Some(&slot_a));
+        assert!(!allocator.slot_for_cell.contains_key(&cell_b));
+    }
+}